@@ -55,7 +55,7 @@ pub fn craft_items<'a, S, C>(
             // If we don't wait here we might bring the window up before
             // the job has changed, leading to the wrong class seeding the
             // window's mode.
-            ui::wait(1.0);
+            ui::wait(handle, 1.0);
 
             job = task.recipe.job;
         } else {
@@ -86,17 +86,17 @@ pub fn craft_items<'a, S, C>(
             status[i].finished += 1;
             status_fn(&status[..]);
             // Check if we received a message to stop from the main thread.
-            ui::wait(2.0);
+            ui::wait(handle, 2.0);
         }
 
         ui::press_escape(handle);
-        ui::wait(2.0);
+        ui::wait(handle, 2.0);
     }
 }
 
 pub fn open_craft_window(handle: xiv::XivHandle) {
-    ui::send_key(handle, 'N' as i32);
-    ui::wait(1.0);
+    ui::open_window(handle, ui::GameWindow::CraftingLog);
+    ui::wait(handle, 1.0);
 }
 
 // Selects the appropriate recipe then leaves the cursor on the Synthesize
@@ -111,17 +111,13 @@ pub fn select_recipe(handle: xiv::XivHandle, task: &task::Task) {
         ui::cursor_backward(handle);
     }
     ui::press_confirm(handle);
-    ui::wait(1.0);
+    ui::wait(handle, 1.0);
     ui::send_string(handle, &task.recipe.name);
     ui::press_enter(handle);
-    ui::wait(1.0);
-    // Navigate to the offset we need
-    for _ in 0..task.recipe.index {
-        ui::cursor_down(handle);
-    }
-
-    // Select the recipe to get to components / sythen
-    ui::press_confirm(handle);
+    ui::wait(handle, 1.0);
+    // Navigate to the offset we need and select the recipe to get to
+    // components / sythen
+    ui::select_index(handle, task.recipe.index);
 }
 
 pub fn select_any_materials(handle: xiv::XivHandle, task: &task::Task) {
@@ -235,7 +231,7 @@ where
 
     // At the end of this sequence the cursor should have selected the recipe
     // again and be on the Synthesize button.
-    ui::wait(3.0);
+    ui::wait(handle, 3.0);
     ui::press_confirm(handle);
     true
 }
@@ -244,6 +240,6 @@ pub fn change_gearset(handle: xiv::XivHandle, gearset: i32) {
     log::info!("changing to gearset {}", gearset);
     ui::press_enter(handle);
     ui::send_string(handle, &format!("/gearset change {}", gearset));
-    ui::wait(0.5);
+    ui::wait(handle, 0.5);
     ui::press_enter(handle);
 }
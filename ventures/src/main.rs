@@ -78,6 +78,20 @@ impl Retainer {
             next: Instant::now() + period,
         }
     }
+
+    // How long until the retainer's venture is complete, or None if it
+    // already is.
+    fn time_remaining(&self, now: Instant) -> Option<Duration> {
+        if self.next > now {
+            Some(self.next - now)
+        } else {
+            None
+        }
+    }
+
+    fn is_complete(&self, now: Instant) -> bool {
+        self.time_remaining(now).is_none()
+    }
 }
 
 const DEFAULT_PERIOD: u64 = 60;
@@ -162,8 +176,7 @@ fn main() -> Result<(), Error> {
     loop {
         // Figure out who the first retainer to be finished is and sleep until then.
         retainers.sort_by_key(|r| r.next);
-        if retainers[0].next > Instant::now() {
-            let sleep_duration = retainers[0].next - Instant::now();
+        if let Some(sleep_duration) = retainers[0].time_remaining(Instant::now()) {
             log::info!(
                 "Retainer {} is next in {}m{}s.",
                 retainers[0].id,
@@ -180,7 +193,7 @@ fn main() -> Result<(), Error> {
         open_retainer_menu(hnd);
         // Run any retainer that finished and update their next venture deadline.
         for r in &mut retainers {
-            if r.next < Instant::now() {
+            if r.is_complete(Instant::now()) {
                 log::info!("re-assigning retainer {}'s venture", r.id);
                 reassign_venture(hnd, r.id);
                 log::debug!("retainer {} done", r.id);
@@ -201,21 +214,17 @@ fn open_retainer_menu(hnd: xiv::XivHandle) {
     ui::press_escape(hnd);
     ui::press_cancel(hnd);
     ui::press_cancel(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
 
     // The reason the menu is opened twice is because we want to clear out any
     // mouse actions the UI registered that would lead to us not having the input
     // cursor up when the retainer menu opens.
-    ui::target_nearest_npc(hnd);
-    ui::wait(1.0);
-    ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::open_window(hnd, ui::GameWindow::RetainerList);
+    ui::wait(hnd, 2.0);
     ui::press_cancel(hnd);
     ui::press_cancel(hnd);
-    ui::target_nearest_npc(hnd);
-    ui::wait(1.0);
-    ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::open_window(hnd, ui::GameWindow::RetainerList);
+    ui::wait(hnd, 2.0);
 }
 
 // General usability rules
@@ -223,41 +232,63 @@ fn open_retainer_menu(hnd: xiv::XivHandle) {
 // 2. Wait 2 seconds after pressing a button for UI changes / Feo Ul / Retainer dialog.
 fn reassign_venture(hnd: xiv::XivHandle, r_id: u64) {
     log::debug!("reassign_venture(r_id: {})", r_id);
-    for _ in 0..r_id - 1 {
-        ui::cursor_down(hnd);
-    }
-    ui::wait(1.0);
+    ui::move_to_index(hnd, 0, (r_id - 1) as usize);
+    ui::wait(hnd, 1.0);
     ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
     ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
     // Move down to Assign Venture / View Venture Progress
-    for _ in 0..5 {
-        ui::cursor_down(hnd);
-    }
-    ui::wait(1.0);
+    ui::move_to_index(hnd, 0, 5);
+    ui::wait(hnd, 1.0);
     // Select the menu option
     ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
     // Move left to 'Reassign'
     ui::cursor_left(hnd);
-    ui::wait(1.0);
+    ui::wait(hnd, 1.0);
     // Confirm 'Reassign'
     ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
     // Move left to 'Assign' in the venture window that comes up
     ui::cursor_left(hnd);
-    ui::wait(1.0);
+    ui::wait(hnd, 1.0);
     // Confirm 'Assign'
     ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
     // Confirm the message from the retainer about the venture
     ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
     // Escape out of the specific retainer's menu
     ui::press_cancel(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
     // Say goodbye to the retainer
     ui::press_confirm(hnd);
-    ui::wait(2.0);
+    ui::wait(hnd, 2.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn venture_time_remaining() {
+        let now = Instant::now();
+        let pending = Retainer {
+            id: 1,
+            period: Duration::from_secs(3600),
+            next: now + Duration::from_secs(90),
+        };
+        assert_eq!(pending.time_remaining(now), Some(Duration::from_secs(90)));
+        assert!(!pending.is_complete(now));
+
+        let complete = Retainer {
+            id: 2,
+            period: Duration::from_secs(3600),
+            next: now,
+        };
+        assert_eq!(complete.time_remaining(now), None);
+        assert!(complete.is_complete(now));
+        assert!(pending.is_complete(now + Duration::from_secs(120)));
+    }
 }
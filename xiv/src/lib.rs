@@ -1,66 +1,506 @@
+pub mod process;
 pub mod ui;
 
-use log;
 use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Error, Result};
+#[cfg(windows)]
 use std::ffi::CStr;
-use winapi::shared::basetsd::LONG_PTR;
-use winapi::shared::minwindef::BOOL;
-use winapi::shared::windef::HWND;
-use winapi::um::winuser::{EnumWindows, GetWindowTextA};
+#[cfg(windows)]
+use winapi::{
+    shared::basetsd::LONG_PTR,
+    shared::minwindef::{BOOL, DWORD, FALSE},
+    shared::windef::HWND,
+    um::handleapi::CloseHandle,
+    um::processthreadsapi::OpenProcess,
+    um::winbase::QueryFullProcessImageNameA,
+    um::winnt::PROCESS_QUERY_LIMITED_INFORMATION,
+    um::winuser::{EnumWindows, GetWindowTextA, GetWindowThreadProcessId},
+};
+
+// Off Windows the handle never points at a real window, but it keeps the same
+// shape so the rest of the crate builds and its tests run anywhere. Input only
+// leaves the process through post_message and send_input in ui, which are
+// no-ops here, so tests drive everything through the ui recorder.
+#[cfg(not(windows))]
+#[allow(clippy::upper_case_acronyms)]
+type HWND = *mut std::ffi::c_void;
+
+pub const JOB_CNT: usize = Crafter::ALL.len();
+pub const JOBS: [&str; JOB_CNT] = {
+    let mut jobs = [""; JOB_CNT];
+    let mut i = 0;
+    while i < JOB_CNT {
+        jobs[i] = Crafter::ALL[i].as_str();
+        i += 1;
+    }
+    jobs
+};
+
+// The crafting jobs, in the order the game lists them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Crafter {
+    Carpenter,
+    Blacksmith,
+    Armorer,
+    Goldsmith,
+    Leatherworker,
+    Weaver,
+    Alchemist,
+    Culinarian,
+}
+
+impl Crafter {
+    pub const ALL: [Crafter; 8] = [
+        Crafter::Carpenter,
+        Crafter::Blacksmith,
+        Crafter::Armorer,
+        Crafter::Goldsmith,
+        Crafter::Leatherworker,
+        Crafter::Weaver,
+        Crafter::Alchemist,
+        Crafter::Culinarian,
+    ];
+
+    // The job's abbreviation, as shown in game.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Crafter::Carpenter => "CRP",
+            Crafter::Blacksmith => "BSM",
+            Crafter::Armorer => "ARM",
+            Crafter::Goldsmith => "GSM",
+            Crafter::Leatherworker => "LTW",
+            Crafter::Weaver => "WVR",
+            Crafter::Alchemist => "ALC",
+            Crafter::Culinarian => "CUL",
+        }
+    }
+
+    // The job's position in JOBS, and in any array indexed by job.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn from_index(index: usize) -> Option<Crafter> {
+        Crafter::ALL.get(index).copied()
+    }
+}
+
+impl fmt::Display for Crafter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Crafter {
+    type Err = Error;
+
+    // Parses a job abbreviation such as "CRP", ignoring case.
+    fn from_str(s: &str) -> Result<Crafter, Error> {
+        Crafter::ALL
+            .iter()
+            .find(|job| job.as_str().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| anyhow!("unknown crafting job {}", s))
+    }
+}
+
+// A time of day in Eorzea, where a day lasts 70 real minutes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EorzeaTime {
+    pub hour: u8,
+    pub minute: u8,
+}
 
-pub const JOB_CNT: usize = 8;
-pub const JOBS: [&str; JOB_CNT] = ["CRP", "BSM", "ARM", "GSM", "LTW", "WVR", "ALC", "CUL"];
+impl EorzeaTime {
+    // Eorzea time runs 3600/175 times faster than real time, counted from the
+    // unix epoch.
+    pub fn from_unix(secs: u64) -> EorzeaTime {
+        let eorzea_secs = secs as u128 * 3600 / 175;
+        EorzeaTime {
+            hour: (eorzea_secs / 3600 % 24) as u8,
+            minute: (eorzea_secs / 60 % 60) as u8,
+        }
+    }
+
+    pub fn now() -> EorzeaTime {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        EorzeaTime::from_unix(secs)
+    }
+}
+
+impl fmt::Display for EorzeaTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+// The game client that owns the window. The legacy DX9 client runs as
+// ffxiv.exe and the current one as ffxiv_dx11.exe.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClientKind {
+    Dx9,
+    Dx11,
+}
+
+impl ClientKind {
+    // Identify the client from the file name, or full path, of its executable.
+    pub fn from_exe_name(name: &str) -> Option<ClientKind> {
+        match file_name(name).to_ascii_lowercase().as_str() {
+            "ffxiv.exe" => Some(ClientKind::Dx9),
+            "ffxiv_dx11.exe" => Some(ClientKind::Dx11),
+            _ => None,
+        }
+    }
+}
 
 // The main handle passed back to library methods. The contents are kept
 // private to avoid leaking any winapi dependencies to callers.
 #[derive(Copy, Clone)]
 pub struct XivHandle {
-    hwnd: HWND,                    // The handle passed back by the winapi
-    pub use_slow_navigation: bool, // Add more delay to XIV navigation
+    hwnd: HWND,                                   // The handle passed back by the winapi
+    pub use_slow_navigation: bool,                // Add more delay to XIV navigation
+    pub keys: ui::KeyBindings,                    // Keys used to navigate the game UI
+    pub cancel: Option<&'static ui::CancelToken>, // Cuts short waits and input when raised
+    pub speed_factor: f32,                        // Multiplier applied to every input delay
+    pub jitter: f32,                              // Fraction of each delay it may randomly vary by
+    pub max_scroll: usize,                        // Most entries a list selection will scroll
+    pub ambiguous: bool,                          // More than one XIV window was found
+    pub client: Option<ClientKind>,               // Which game client owns the window, if known
+    pub dry_run: bool,                            // Log input and waits rather than doing them
+    pub input_method: ui::InputMethod,            // How input is delivered to the game
+}
+
+impl XivHandle {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn new(hwnd: HWND) -> XivHandle {
+        XivHandle {
+            hwnd,
+            use_slow_navigation: false,
+            keys: ui::KeyBindings::default(),
+            cancel: None,
+            speed_factor: 1.0,
+            jitter: 0.0,
+            max_scroll: ui::DEFAULT_MAX_SCROLL,
+            ambiguous: false,
+            client: None,
+            dry_run: false,
+            input_method: ui::InputMethod::default(),
+        }
+    }
+
+    // The underlying window handle, for screen capture or overlay libraries
+    // that need to find the game window themselves. This is an escape hatch:
+    // nothing here keeps the handle valid once the game closes, and input sent
+    // to it directly bypasses cancellation, recording and pacing.
+    #[cfg(feature = "raw-hwnd")]
+    pub fn raw_hwnd(&self) -> isize {
+        self.hwnd as isize
+    }
 }
 
 impl fmt::Debug for XivHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Xivhandle {{ {} }}", self.hwnd as LONG_PTR as u64)
+        write!(f, "Xivhandle {{ {} }}", self.hwnd as isize as u64)
+    }
+}
+
+// The final component of |path|, which may use either kind of slash.
+fn file_name(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
+
+// Decides whether a window is the game from its title and the full path of
+// its executable, if that could be found.
+pub type WindowPredicate = Box<dyn Fn(&str, Option<&str>) -> bool>;
+
+// Picks out the game window for init_with_filter.
+pub enum WindowFilter {
+    Title(String),           // The window title matches exactly
+    TitleContains(String),   // The window title contains this
+    ExeName(String),         // The owning executable has this file name, ignoring case
+    Custom(WindowPredicate), // A user supplied check
+}
+
+impl WindowFilter {
+    // |exe| looks up the executable's path and is only called when needed.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn matches<E: FnOnce() -> Option<String>>(&self, title: &str, exe: E) -> bool {
+        match self {
+            WindowFilter::Title(t) => title == t,
+            WindowFilter::TitleContains(t) => title.contains(t.as_str()),
+            WindowFilter::ExeName(name) => {
+                matches!(exe(), Some(path) if file_name(&path).eq_ignore_ascii_case(name))
+            }
+            WindowFilter::Custom(f) => f(title, exe().as_deref()),
+        }
+    }
+}
+
+// Matches the title of either game client.
+impl Default for WindowFilter {
+    fn default() -> WindowFilter {
+        WindowFilter::TitleContains("FINAL FANTASY XIV".to_string())
     }
 }
 
 #[cfg(windows)]
 pub fn init() -> Result<XivHandle, Error> {
-    let mut arg = std::ptr::null_mut();
+    init_with_filter(WindowFilter::default())
+}
+
+// Like init, but attaches to the first window matching |filter|, for private
+// servers or renamed executables.
+#[cfg(windows)]
+pub fn init_with_filter(filter: WindowFilter) -> Result<XivHandle, Error> {
+    let handle = attach(&enumerate_windows(), &filter, exe_name)
+        .ok_or_else(|| anyhow!("Unable to find XIV window! Is Final Fantasy XIV running?"))?;
+    log::info!("Found FFXIV.\n");
+    if !process::is_elevated() {
+        log::warn!(
+            "Not running as administrator. If FFXIV was started as administrator it will ignore all input"
+        );
+    }
+    Ok(handle)
+}
+
+// Builds the handle for the window in |windows| that matches |filter|, with
+// |exe_name| used to find the executable behind each window.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn attach<E>(windows: &[(HWND, String)], filter: &WindowFilter, exe_name: E) -> Option<XivHandle>
+where
+    E: Fn(HWND) -> Option<String>,
+{
+    let (hwnd, matches) = find_window(windows, filter, &exe_name)?;
+    let mut handle = XivHandle::new(hwnd);
+    handle.client = exe_name(hwnd).and_then(|name| ClientKind::from_exe_name(&name));
+    match handle.client {
+        Some(client) => log::debug!("client is {:?}", client),
+        None => log::warn!("Unable to tell which XIV client is running"),
+    }
+    if matches > 1 {
+        log::warn!(
+            "Found {} XIV windows, input will go to whichever was listed first",
+            matches
+        );
+        handle.ambiguous = true;
+    }
+    Some(handle)
+}
+
+// Picks the XIV window out of |windows|, a list of window handles and their
+// titles, using |exe_name| to find the executable behind a window. Returns the
+// first match along with how many windows matched in total.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn find_window<E>(
+    windows: &[(HWND, String)],
+    filter: &WindowFilter,
+    exe_name: E,
+) -> Option<(HWND, usize)>
+where
+    E: Fn(HWND) -> Option<String>,
+{
+    let mut matches = windows
+        .iter()
+        .filter(|(hwnd, title)| filter.matches(title, || exe_name(*hwnd)));
+    let (hwnd, _) = matches.next()?;
+    Some((*hwnd, matches.count() + 1))
+}
+
+// The full path of the executable that owns |hwnd|.
+#[cfg(windows)]
+fn exe_name(hwnd: HWND) -> Option<String> {
+    let mut path: Vec<i8> = vec![0; 260];
+    let mut len = path.len() as DWORD;
     unsafe {
-        // TODO: Figure out Rust error handling rather than just panicking inside a lib
-        // method.
-        match EnumWindows(Some(enum_callback), &mut arg as *mut HWND as LONG_PTR) {
-            0 => Ok(XivHandle {
-                hwnd: arg as HWND,
-                use_slow_navigation: false,
-            }),
-            _ => Err(anyhow!(
-                "Unable to find XIV window! Is Final Fantasy XIV running?"
-            )),
+        let mut pid: DWORD = 0;
+        GetWindowThreadProcessId(hwnd, &mut pid);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if process.is_null() {
+            return None;
         }
+        let ok = QueryFullProcessImageNameA(process, 0, path.as_mut_ptr(), &mut len);
+        CloseHandle(process);
+        if ok == 0 {
+            return None;
+        }
+        Some(CStr::from_ptr(path.as_ptr()).to_string_lossy().into_owned())
+    }
+}
+
+// Lists every top level window along with its title.
+#[cfg(windows)]
+fn enumerate_windows() -> Vec<(HWND, String)> {
+    let mut windows: Vec<(HWND, String)> = Vec::new();
+    unsafe {
+        EnumWindows(
+            Some(enum_callback),
+            &mut windows as *mut Vec<(HWND, String)> as LONG_PTR,
+        );
     }
+    windows
 }
 
 // This callback is called for every window the user32 EnumWindows call finds
-// while walking the window list. It's used to find the XIV window by title.
-//
-// To be more foolproof checking process name might be better.
+// while walking the window list. Each titled window is added to the list
+// passed in |arg| so the XIV window can be found by title, or by process
+// name as init_with_filter's WindowFilter::ExeName does.
+#[cfg(windows)]
 unsafe extern "system" fn enum_callback(win_hwnd: HWND, arg: LONG_PTR) -> BOOL {
     let mut title: Vec<i8> = vec![0; 256];
-    let xiv_hwnd = arg as *mut HWND;
+    let windows = &mut *(arg as *mut Vec<(HWND, String)>);
 
     if GetWindowTextA(win_hwnd, title.as_mut_ptr(), title.len() as i32) > 0 {
         let title = CStr::from_ptr(title.as_ptr()).to_string_lossy();
-        log::trace!("found {}: {:?}, arg {:?}", title, win_hwnd, xiv_hwnd);
-        if title.contains("FINAL FANTASY XIV") {
-            log::info!("Found FFXIV.\n");
-            *xiv_hwnd = win_hwnd;
-            return 0;
-        }
+        log::trace!("found {}: {:?}", title, win_hwnd);
+        windows.push((win_hwnd, title.into_owned()));
     }
     1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_exe(_: HWND) -> Option<String> {
+        None
+    }
+
+    fn test_exe(hwnd: HWND) -> Option<String> {
+        match hwnd as usize {
+            1 => Some(r"C:\Windows\explorer.exe".to_string()),
+            2 => Some(r"D:\XIV\game\ffxiv_dx11.exe".to_string()),
+            3 => Some(r"D:\Private\Client.exe".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn find_single_window() {
+        let windows = vec![
+            (1 as HWND, "Program Manager".to_string()),
+            (2 as HWND, "FINAL FANTASY XIV".to_string()),
+        ];
+        assert_eq!(
+            find_window(&windows, &WindowFilter::default(), no_exe),
+            Some((2 as HWND, 1))
+        );
+        assert_eq!(
+            find_window(&windows[..1], &WindowFilter::default(), no_exe),
+            None
+        );
+    }
+
+    #[test]
+    fn find_multiple_windows() {
+        let windows = vec![
+            (1 as HWND, "FINAL FANTASY XIV".to_string()),
+            (2 as HWND, "Program Manager".to_string()),
+            (3 as HWND, "FINAL FANTASY XIV".to_string()),
+        ];
+        assert_eq!(
+            find_window(&windows, &WindowFilter::default(), no_exe),
+            Some((1 as HWND, 2))
+        );
+
+        let handle = attach(&windows, &WindowFilter::default(), test_exe).unwrap();
+        assert_eq!(handle.hwnd, 1 as HWND);
+        assert!(handle.ambiguous);
+        let handle = attach(&windows[1..], &WindowFilter::default(), test_exe).unwrap();
+        assert_eq!(handle.hwnd, 3 as HWND);
+        assert!(!handle.ambiguous);
+        assert_eq!(handle.client, None);
+        let windows = vec![(2 as HWND, "FINAL FANTASY XIV".to_string())];
+        let handle = attach(&windows, &WindowFilter::default(), test_exe).unwrap();
+        assert!(!handle.ambiguous);
+        assert_eq!(handle.client, Some(ClientKind::Dx11));
+        assert!(attach(&windows[..0], &WindowFilter::default(), test_exe).is_none());
+    }
+
+    #[test]
+    fn window_filters() {
+        let windows = vec![
+            (1 as HWND, "Program Manager".to_string()),
+            (2 as HWND, "FINAL FANTASY XIV".to_string()),
+            (3 as HWND, "FINAL FANTASY XIV (Private)".to_string()),
+        ];
+        let find = |filter| find_window(&windows, &filter, test_exe);
+        assert_eq!(
+            find(WindowFilter::Title("FINAL FANTASY XIV".to_string())),
+            Some((2 as HWND, 1))
+        );
+        assert_eq!(
+            find(WindowFilter::TitleContains("(Private)".to_string())),
+            Some((3 as HWND, 1))
+        );
+        assert_eq!(find(WindowFilter::default()), Some((2 as HWND, 2)));
+        assert_eq!(
+            find(WindowFilter::ExeName("client.EXE".to_string())),
+            Some((3 as HWND, 1))
+        );
+        assert_eq!(find(WindowFilter::ExeName("ffxiv.exe".to_string())), None);
+        assert_eq!(
+            find(WindowFilter::Custom(Box::new(|title, exe| {
+                title.starts_with("Program") && exe.unwrap().ends_with("explorer.exe")
+            }))),
+            Some((1 as HWND, 1))
+        );
+    }
+
+    #[test]
+    fn eorzea_time_from_unix() {
+        let et = |hour, minute| EorzeaTime { hour, minute };
+        assert_eq!(EorzeaTime::from_unix(0), et(0, 0));
+        // An Eorzean hour is 175 seconds and a day 70 minutes.
+        assert_eq!(EorzeaTime::from_unix(175), et(1, 0));
+        assert_eq!(EorzeaTime::from_unix(174), et(0, 59));
+        assert_eq!(EorzeaTime::from_unix(4200), et(0, 0));
+        assert_eq!(
+            EorzeaTime::from_unix(4200 * 1000 + 175 * 13 + 35),
+            et(13, 12)
+        );
+        assert_eq!(et(7, 5).to_string(), "07:05");
+    }
+
+    #[test]
+    fn crafter_matches_jobs() {
+        assert_eq!(JOB_CNT, 8);
+        for (i, job) in Crafter::ALL.iter().enumerate() {
+            assert_eq!(job.index(), i);
+            assert_eq!(job.as_str(), JOBS[i]);
+            assert_eq!(Crafter::from_index(i), Some(*job));
+            assert_eq!(JOBS[i].parse::<Crafter>().unwrap(), *job);
+        }
+        assert_eq!(Crafter::from_index(JOB_CNT), None);
+        assert_eq!("wvr".parse::<Crafter>().unwrap(), Crafter::Weaver);
+        assert!("MIN".parse::<Crafter>().is_err());
+    }
+
+    #[test]
+    fn client_from_exe_name() {
+        assert_eq!(
+            ClientKind::from_exe_name("ffxiv.exe"),
+            Some(ClientKind::Dx9)
+        );
+        assert_eq!(
+            ClientKind::from_exe_name(r"C:\Games\FINAL FANTASY XIV\game\ffxiv_dx11.exe"),
+            Some(ClientKind::Dx11)
+        );
+        assert_eq!(
+            ClientKind::from_exe_name("FFXIV_DX11.EXE"),
+            Some(ClientKind::Dx11)
+        );
+        assert_eq!(ClientKind::from_exe_name("ffxivboot.exe"), None);
+    }
+
+    #[cfg(feature = "raw-hwnd")]
+    #[test]
+    fn raw_hwnd() {
+        let handle = XivHandle::new(42 as HWND);
+        assert_eq!(handle.raw_hwnd(), 42);
+    }
+}
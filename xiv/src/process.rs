@@ -0,0 +1,47 @@
+// Checks on the process running the library, as opposed to the game.
+
+#[cfg(windows)]
+use {
+    std::mem,
+    winapi::shared::minwindef::{DWORD, LPVOID},
+    winapi::um::handleapi::CloseHandle,
+    winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken},
+    winapi::um::securitybaseapi::GetTokenInformation,
+    winapi::um::winnt::{TokenElevation, HANDLE, TOKEN_ELEVATION, TOKEN_QUERY},
+};
+
+// Whether this process is running as administrator. Windows won't deliver
+// posted input from a normal process to one running elevated, so if the game
+// was started as administrator this has to be too. Returns false if the token
+// can't be queried.
+#[cfg(windows)]
+pub fn is_elevated() -> bool {
+    let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+    let mut len: DWORD = 0;
+    unsafe {
+        let mut token: HANDLE = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+        let ok = GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as LPVOID,
+            mem::size_of::<TOKEN_ELEVATION>() as DWORD,
+            &mut len,
+        );
+        CloseHandle(token);
+        ok != 0 && elevation.TokenIsElevated != 0
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elevation_check_runs() {
+        // CI may or may not be elevated, either answer is fine.
+        let _ = is_elevated();
+    }
+}
@@ -1,9 +1,22 @@
 #[cfg(windows)]
-use {winapi::shared::minwindef::UINT, winapi::um::winuser::PostMessageA};
+use {
+    clipboard::{ClipboardContext, ClipboardProvider},
+    winapi::ctypes::c_int,
+    winapi::shared::minwindef::{DWORD, UINT},
+    winapi::shared::windef::POINT,
+    winapi::um::winuser::*,
+};
 
+use anyhow::{anyhow, Error, Result};
+use lazy_static::lazy_static;
 use log;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // This module handles all interactions with the game UI.
 
@@ -15,7 +28,25 @@ const CHAR_DELAY: f32 = 0.05;
 // These are affected by latency and in testing 200 milliseconds
 // seems safe in laggier conditions.
 const UI_DELAY: f32 = 0.1;
-const UI_DELAY_SLOW: f32 = 0.2;
+// Every delay is multiplied by this when slow navigation is enabled, which
+// gives the 200 milliseconds above for navigation.
+const SLOW_NAVIGATION_FACTOR: f32 = 2.0;
+// Time given to the UI to settle after input before checking whether it has
+// reached the state we expect.
+const VERIFY_DELAY: f32 = 1.0;
+// Default for the furthest a list selection will scroll, see max_scroll.
+pub const DEFAULT_MAX_SCROLL: usize = 100;
+// How often a cancellable wait checks whether it has been cancelled.
+const CANCEL_POLL: Duration = Duration::from_millis(10);
+
+// Enter a tracing span for the rest of the enclosing block. Compiles away
+// entirely unless the tracing feature is enabled.
+macro_rules! nav_span {
+    ($level:ident, $($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(tracing::Level::$level, $($args)*).entered();
+    };
+}
 
 #[cfg(windows)]
 mod constants {
@@ -32,112 +63,836 @@ mod constants {
     pub const KEY_ESCAPE: i32 = VK_ESCAPE;
     pub const KEY_BACKSPACE: i32 = VK_BACK;
     pub const KEY_SUBCOMMANDS: i32 = VK_HOME;
+    pub const KEY_CONTROL: i32 = VK_CONTROL;
+    pub const KEY_SHIFT: i32 = VK_SHIFT;
+    pub const KEY_ALT: i32 = VK_MENU;
+    pub const KEY_MINUS: i32 = VK_OEM_MINUS;
+    pub const KEY_EQUALS: i32 = VK_OEM_PLUS;
     pub const MSG_KEY_UP: u32 = WM_KEYUP;
     pub const MSG_KEY_DOWN: u32 = WM_KEYDOWN;
     pub const MSG_KEY_CHAR: u32 = WM_CHAR;
+    pub const MSG_LBUTTON_DOWN: u32 = WM_LBUTTONDOWN;
+    pub const MSG_LBUTTON_UP: u32 = WM_LBUTTONUP;
+    pub const MSG_RBUTTON_DOWN: u32 = WM_RBUTTONDOWN;
+    pub const MSG_RBUTTON_UP: u32 = WM_RBUTTONUP;
+    pub const MOUSE_LBUTTON: i32 = MK_LBUTTON as i32;
+    pub const MOUSE_RBUTTON: i32 = MK_RBUTTON as i32;
 }
 
 #[cfg(not(windows))]
 mod constants {
-    pub const KEY_UP: i32 = 0;
-    pub const KEY_DOWN: i32 = 0;
-    pub const KEY_LEFT: i32 = 0;
-    pub const KEY_RIGHT: i32 = 0;
-    pub const KEY_CONFIRM: i32 = 0;
-    pub const KEY_FORWARD: i32 = 0;
-    pub const KEY_BACKWARD: i32 = 0;
-    pub const KEY_CANCEL: i32 = 0;
-    pub const KEY_ENTER: i32 = 0;
-    pub const KEY_BACKSPACE: i32 = 0;
-    pub const KEY_ESCAPE: i32 = 0;
-    pub const KEY_SUBCOMMANDS: i32 = 0;
-    pub const MSG_KEY_UP: u32 = 0;
-    pub const MSG_KEY_DOWN: u32 = 0;
-    pub const MSG_KEY_CHAR: u32 = 0;
-}
-
-// Wait |s| seconds, fractions permitted.
-pub fn wait(s: f32) {
+    // The same Win32 values as above, so recordings can tell keys and
+    // messages apart.
+    pub const KEY_UP: i32 = 0x68; // VK_NUMPAD8
+    pub const KEY_DOWN: i32 = 0x62; // VK_NUMPAD2
+    pub const KEY_LEFT: i32 = 0x64; // VK_NUMPAD4
+    pub const KEY_RIGHT: i32 = 0x66; // VK_NUMPAD6
+    pub const KEY_CONFIRM: i32 = 0x60; // VK_NUMPAD0
+    pub const KEY_FORWARD: i32 = 0x69; // VK_NUMPAD9
+    pub const KEY_BACKWARD: i32 = 0x67; // VK_NUMPAD7
+    pub const KEY_CANCEL: i32 = 0x6E; // VK_DECIMAL
+    pub const KEY_ENTER: i32 = 0x0D; // VK_RETURN
+    pub const KEY_BACKSPACE: i32 = 0x08; // VK_BACK
+    pub const KEY_ESCAPE: i32 = 0x1B; // VK_ESCAPE
+    pub const KEY_SUBCOMMANDS: i32 = 0x24; // VK_HOME
+    pub const KEY_CONTROL: i32 = 0x11; // VK_CONTROL
+    pub const KEY_SHIFT: i32 = 0x10; // VK_SHIFT
+    pub const KEY_ALT: i32 = 0x12; // VK_MENU
+    pub const KEY_MINUS: i32 = 0xBD; // VK_OEM_MINUS
+    pub const KEY_EQUALS: i32 = 0xBB; // VK_OEM_PLUS
+    pub const MSG_KEY_UP: u32 = 0x101; // WM_KEYUP
+    pub const MSG_KEY_DOWN: u32 = 0x100; // WM_KEYDOWN
+    pub const MSG_KEY_CHAR: u32 = 0x102; // WM_CHAR
+    pub const MSG_LBUTTON_DOWN: u32 = 0x201; // WM_LBUTTONDOWN
+    pub const MSG_LBUTTON_UP: u32 = 0x202; // WM_LBUTTONUP
+    pub const MSG_RBUTTON_DOWN: u32 = 0x204; // WM_RBUTTONDOWN
+    pub const MSG_RBUTTON_UP: u32 = 0x205; // WM_RBUTTONUP
+    pub const MOUSE_LBUTTON: i32 = 0x01; // MK_LBUTTON
+    pub const MOUSE_RBUTTON: i32 = 0x02; // MK_RBUTTON
+}
+
+// Modifier keys for send_key_with_modifier.
+pub use constants::{KEY_ALT, KEY_CONTROL, KEY_SHIFT};
+
+// The keys used to drive the game. UI navigation defaults to the game's numpad
+// layout and everything else to its standard keyboard binds, but any of them
+// can be replaced on the handle for anyone who has remapped them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    pub up: i32,
+    pub down: i32,
+    pub left: i32,
+    pub right: i32,
+    pub confirm: i32,
+    pub forward: i32,
+    pub backward: i32,
+    pub cancel: i32,
+    pub enter: i32,
+    pub escape: i32,
+    pub backspace: i32,
+    pub subcommands: i32,
+    pub move_forward: i32,
+    pub move_backward: i32,
+    pub strafe_left: i32,
+    pub strafe_right: i32,
+    pub hotbar: [i32; 12], // Slots 1 through 12 of the first hotbar
+    pub crafting_log: i32,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            up: constants::KEY_UP,
+            down: constants::KEY_DOWN,
+            left: constants::KEY_LEFT,
+            right: constants::KEY_RIGHT,
+            confirm: constants::KEY_CONFIRM,
+            forward: constants::KEY_FORWARD,
+            backward: constants::KEY_BACKWARD,
+            cancel: constants::KEY_CANCEL,
+            enter: constants::KEY_ENTER,
+            escape: constants::KEY_ESCAPE,
+            backspace: constants::KEY_BACKSPACE,
+            subcommands: constants::KEY_SUBCOMMANDS,
+            move_forward: 'W' as i32,
+            move_backward: 'S' as i32,
+            strafe_left: 'Q' as i32,
+            strafe_right: 'E' as i32,
+            hotbar: [
+                '1' as i32,
+                '2' as i32,
+                '3' as i32,
+                '4' as i32,
+                '5' as i32,
+                '6' as i32,
+                '7' as i32,
+                '8' as i32,
+                '9' as i32,
+                '0' as i32,
+                constants::KEY_MINUS,
+                constants::KEY_EQUALS,
+            ],
+            crafting_log: 'N' as i32,
+        }
+    }
+}
+
+// How input reaches the game.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum InputMethod {
+    // Post messages straight to the game window's queue. This works while the
+    // game is in the background.
+    #[default]
+    PostMessage,
+    // Inject OS level input with SendInput, which some UI responds to more
+    // reliably. The game window is brought to the foreground before every
+    // message, taking focus from whatever else is in use. Input is never
+    // injected unless the game window has focus, so if Windows refuses to
+    // hand it over the input is dropped rather than sent to another app.
+    SendInput,
+}
+
+// A window message translated into what SendInput needs to reproduce it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SyntheticInput {
+    Key {
+        key: u16,
+        up: bool,
+    },
+    Char(u16),
+    Mouse {
+        right: bool,
+        up: bool,
+        x: i32,
+        y: i32,
+    },
+}
+
+fn synthetic_input(msg: u32, key: i32, lparam: isize) -> Option<SyntheticInput> {
+    // The inverse of pack_coordinates.
+    let x = (lparam & 0xFFFF) as i16 as i32;
+    let y = ((lparam >> 16) & 0xFFFF) as i16 as i32;
+    let input = match msg {
+        constants::MSG_KEY_DOWN => SyntheticInput::Key {
+            key: key as u16,
+            up: false,
+        },
+        constants::MSG_KEY_UP => SyntheticInput::Key {
+            key: key as u16,
+            up: true,
+        },
+        constants::MSG_KEY_CHAR => SyntheticInput::Char(key as u16),
+        constants::MSG_LBUTTON_DOWN => SyntheticInput::Mouse {
+            right: false,
+            up: false,
+            x,
+            y,
+        },
+        constants::MSG_LBUTTON_UP => SyntheticInput::Mouse {
+            right: false,
+            up: true,
+            x,
+            y,
+        },
+        constants::MSG_RBUTTON_DOWN => SyntheticInput::Mouse {
+            right: true,
+            up: false,
+            x,
+            y,
+        },
+        constants::MSG_RBUTTON_UP => SyntheticInput::Mouse {
+            right: true,
+            up: true,
+            x,
+            y,
+        },
+        _ => return None,
+    };
+    Some(input)
+}
+
+// A single piece of input produced by this module, in the order it would be
+// sent to the game. Only used when input is being recorded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    Message(u32, i32, isize), // (msg, key, lparam) as passed to the XIV window
+    Input(SyntheticInput),    // Input sent with SendInput instead of a message
+    Wait(Duration),
+}
+
+// Input captured by record, along with a clock that only moves forward as
+// waits are recorded.
+struct Recorder {
+    events: Vec<Event>,
+    start: Instant,
+    elapsed: Duration,
+    next_char: Option<Instant>,
+    on_event: Option<fn(&Event)>,
+}
+
+thread_local! {
+    // When set, all input on this thread is appended here rather than being
+    // posted to the game, and waits are recorded rather than slept.
+    static RECORDER: RefCell<Option<Recorder>> = const { RefCell::new(None) };
+}
+
+// Run |f| with all input on the current thread recorded instead of sent to the
+// game and return the sequence of events it produced. This allows navigation
+// sequences to be checked without a running game.
+pub fn record<F: FnOnce()>(f: F) -> Vec<Event> {
+    record_with(f, None)
+}
+
+// Like record, but calls |on_event| as each event is recorded, e.g. to cancel
+// part way through a sequence.
+fn record_with<F: FnOnce()>(f: F, on_event: Option<fn(&Event)>) -> Vec<Event> {
+    RECORDER.with(|r| {
+        *r.borrow_mut() = Some(Recorder {
+            events: Vec::new(),
+            start: Instant::now(),
+            elapsed: Duration::from_secs(0),
+            next_char: None,
+            on_event,
+        })
+    });
+    f();
+    RECORDER.with(|r| r.borrow_mut().take().map(|r| r.events).unwrap_or_default())
+}
+
+// Append |event| to the recorder if one is active. Returns false if the event
+// should be carried out for real.
+fn record_event(event: Event) -> bool {
+    RECORDER.with(|r| match r.borrow_mut().as_mut() {
+        Some(recorder) => {
+            if let Event::Wait(duration) = event {
+                recorder.elapsed += duration;
+            }
+            if let Some(on_event) = recorder.on_event {
+                on_event(&event);
+            }
+            recorder.events.push(event);
+            true
+        }
+        None => false,
+    })
+}
+
+lazy_static! {
+    // When the next character may be posted to each window, keyed by its
+    // handle.
+    static ref NEXT_CHAR: Mutex<HashMap<usize, Instant>> = Mutex::new(HashMap::new());
+}
+
+// Hold back a character until the window is ready for the next one. Unless
+// send_char says otherwise, that is CHAR_DELAY after the last character, since
+// the input buffer overflows under lag if anything posts characters back to
+// back.
+fn pace_char(xiv_handle: super::XivHandle) {
+    if let Some(next) = next_char(xiv_handle) {
+        let now = now();
+        if now < next {
+            sleep_handle(xiv_handle, next - now);
+        }
+    }
+    let interval = Duration::from_millis((base_delay(xiv_handle, CHAR_DELAY) * 1000_f32) as u64);
+    set_next_char(xiv_handle, now() + interval);
+}
+
+fn next_char(xiv_handle: super::XivHandle) -> Option<Instant> {
+    RECORDER.with(|r| match r.borrow().as_ref() {
+        Some(recorder) => recorder.next_char,
+        None => NEXT_CHAR
+            .lock()
+            .unwrap()
+            .get(&(xiv_handle.hwnd as usize))
+            .copied(),
+    })
+}
+
+fn set_next_char(xiv_handle: super::XivHandle, time: Instant) {
+    RECORDER.with(|r| match r.borrow_mut().as_mut() {
+        Some(recorder) => recorder.next_char = Some(time),
+        None => {
+            NEXT_CHAR
+                .lock()
+                .unwrap()
+                .insert(xiv_handle.hwnd as usize, time);
+        }
+    });
+}
+
+thread_local! {
+    // Time skipped by dry run waits on this thread.
+    static DRY_RUN_ELAPSED: Cell<Duration> = const { Cell::new(Duration::from_secs(0)) };
+}
+
+// The current time. While recording, time only passes through recorded waits
+// so that timeouts play out the same as they would against the game. Dry run
+// waits return straight away but still move the clock on, for the same reason.
+fn now() -> Instant {
+    RECORDER.with(|r| match r.borrow().as_ref() {
+        Some(recorder) => recorder.start + recorder.elapsed,
+        None => Instant::now() + DRY_RUN_ELAPSED.with(Cell::get),
+    })
+}
+
+// A flag that can be raised from another thread, or a Ctrl+C handler, to cut
+// short any waits in progress. Tokens are intended to live in a static so that
+// a reference can be kept on the copyable XivHandle.
+#[derive(Debug, Default)]
+pub struct CancelToken(AtomicBool);
+
+impl CancelToken {
+    pub const fn new() -> Self {
+        CancelToken(AtomicBool::new(false))
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Wait |s| seconds, fractions permitted. The wait is scaled by the handle's
+// speed factor and slow navigation setting, and cut short if its cancel token
+// is raised.
+pub fn wait(xiv_handle: super::XivHandle, s: f32) {
+    sleep_handle(xiv_handle, delay(xiv_handle, s));
+}
+
+// Sleep for exactly |duration|, cut short if the handle's cancel token is
+// raised.
+fn sleep_handle(xiv_handle: super::XivHandle, duration: Duration) {
+    if xiv_handle.dry_run {
+        if !record_event(Event::Wait(duration)) {
+            log::info!("dry run: wait {:?}", duration);
+            DRY_RUN_ELAPSED.with(|elapsed| elapsed.set(elapsed.get() + duration));
+        }
+        return;
+    }
+    match xiv_handle.cancel {
+        Some(token) => {
+            sleep_cancellable(duration, token);
+        }
+        None => {
+            if !record_event(Event::Wait(duration)) {
+                sleep(duration);
+            }
+        }
+    }
+}
+
+// Wait |s| seconds, returning early if |token| is cancelled. Returns false if
+// the wait did not run to completion.
+pub fn wait_cancellable(s: f32, token: &CancelToken) -> bool {
     let ms = (s * 1000_f32) as u64;
-    sleep(Duration::from_millis(ms));
+    sleep_cancellable(Duration::from_millis(ms), token)
+}
+
+fn sleep_cancellable(duration: Duration, token: &CancelToken) -> bool {
+    if record_event(Event::Wait(duration)) {
+        return !token.is_cancelled();
+    }
+
+    let deadline = Instant::now() + duration;
+    loop {
+        if token.is_cancelled() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        sleep(std::cmp::min(deadline - now, CANCEL_POLL));
+    }
+}
+
+// The real duration of a |s| second delay once the handle's speed factor,
+// slow navigation and jitter have been applied.
+fn delay(xiv_handle: super::XivHandle, s: f32) -> Duration {
+    let mut s = base_delay(xiv_handle, s);
+    if xiv_handle.jitter > 0.0 {
+        // Move the delay by up to |jitter| of itself in either direction.
+        s += s * xiv_handle.jitter * (next_random() * 2.0 - 1.0);
+    }
+    let ms = (s * 1000_f32) as u64;
+    Duration::from_millis(ms)
+}
+
+// |s| scaled by the handle's speed factor and slow navigation, without jitter.
+fn base_delay(xiv_handle: super::XivHandle, s: f32) -> f32 {
+    let s = s * xiv_handle.speed_factor;
+    if xiv_handle.use_slow_navigation {
+        s * SLOW_NAVIGATION_FACTOR
+    } else {
+        s
+    }
+}
+
+thread_local! {
+    // xorshift state used for jitter. Seeded from the clock unless a test
+    // needs a reproducible sequence.
+    static JITTER_STATE: Cell<u64> = Cell::new(clock_seed());
+}
+
+fn clock_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    nanos | 1
+}
+
+// Seed the jitter applied to delays on the current thread so that they repeat
+// from run to run.
+pub fn seed_jitter(seed: u64) {
+    // xorshift gets stuck at zero, so make sure the state never is.
+    JITTER_STATE.with(|state| state.set(seed | 1));
+}
+
+// Returns a number in [0, 1).
+fn next_random() -> f32 {
+    JITTER_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 40) as f32 / (1 << 24) as f32
+    })
+}
+
+fn is_cancelled(xiv_handle: super::XivHandle) -> bool {
+    match xiv_handle.cancel {
+        Some(token) => token.is_cancelled(),
+        None => false,
+    }
 }
 
 pub fn cursor_down(xiv_handle: super::XivHandle) {
     log::debug!("[down]");
-    send_key(xiv_handle, constants::KEY_DOWN);
+    send_key(xiv_handle, xiv_handle.keys.down);
 }
 
 pub fn cursor_up(xiv_handle: super::XivHandle) {
     log::debug!("[up]");
-    send_key(xiv_handle, constants::KEY_UP);
+    send_key(xiv_handle, xiv_handle.keys.up);
 }
 
 pub fn cursor_left(xiv_handle: super::XivHandle) {
     log::debug!("[left]");
-    send_key(xiv_handle, constants::KEY_LEFT);
+    send_key(xiv_handle, xiv_handle.keys.left);
 }
 
 pub fn cursor_right(xiv_handle: super::XivHandle) {
     log::debug!("[right]");
-    send_key(xiv_handle, constants::KEY_RIGHT);
+    send_key(xiv_handle, xiv_handle.keys.right);
 }
 
 pub fn cursor_backward(xiv_handle: super::XivHandle) {
     log::debug!("[ui back]");
-    send_key(xiv_handle, constants::KEY_BACKWARD)
+    send_key(xiv_handle, xiv_handle.keys.backward)
 }
 
 pub fn cursor_forward(xiv_handle: super::XivHandle) {
     log::debug!("[ui forward]");
-    send_key(xiv_handle, constants::KEY_FORWARD);
+    send_key(xiv_handle, xiv_handle.keys.forward);
 }
 
 pub fn press_backspace(xiv_handle: super::XivHandle) {
     log::debug!("[backspace]");
-    send_key(xiv_handle, constants::KEY_BACKSPACE);
+    send_key(xiv_handle, xiv_handle.keys.backspace);
 }
 
 pub fn press_confirm(xiv_handle: super::XivHandle) {
     log::debug!("[confirm]");
-    send_key(xiv_handle, constants::KEY_CONFIRM);
+    send_key(xiv_handle, xiv_handle.keys.confirm);
 }
 
 pub fn press_cancel(xiv_handle: super::XivHandle) {
     log::debug!("[cancel]");
-    send_key(xiv_handle, constants::KEY_CANCEL);
+    send_key(xiv_handle, xiv_handle.keys.cancel);
 }
 
 pub fn press_enter(xiv_handle: super::XivHandle) {
     log::debug!("[enter]");
-    send_key(xiv_handle, constants::KEY_ENTER);
+    send_key(xiv_handle, xiv_handle.keys.enter);
 }
 
 pub fn press_escape(xiv_handle: super::XivHandle) {
     log::debug!("[esc]");
-    send_key(xiv_handle, constants::KEY_ESCAPE);
+    send_key(xiv_handle, xiv_handle.keys.escape);
 }
 
 pub fn press_subcommands(xiv_handle: super::XivHandle) {
     log::debug!("[subcommands]");
-    send_key(xiv_handle, constants::KEY_SUBCOMMANDS);
+    send_key(xiv_handle, xiv_handle.keys.subcommands);
 }
 
-pub fn target_nearest_npc(xiv_handle: super::XivHandle) {
+// Press the key bound to hotbar |slot|. By default slots 1 through 9 are the
+// number keys, 10 is 0, and 11 and 12 are - and =.
+pub fn press_hotbar(xiv_handle: super::XivHandle, slot: u8) -> Result<(), Error> {
+    let key = match slot {
+        1..=12 => xiv_handle.keys.hotbar[slot as usize - 1],
+        _ => return Err(anyhow!("Hotbar slot {} is out of range (1-12)", slot)),
+    };
+    log::debug!("[hotbar {}]", slot);
+    send_key(xiv_handle, key);
+    Ok(())
+}
+
+// Scroll from the list entry at |current| to the one at |target|. The number
+// of presses is capped by the handle's max_scroll in case of a bad index.
+pub fn move_to_index(xiv_handle: super::XivHandle, current: usize, target: usize) {
+    nav_span!(DEBUG, "move_to_index", current, target);
+    let (distance, cursor): (usize, fn(super::XivHandle)) = if target < current {
+        (current - target, cursor_up)
+    } else {
+        (target - current, cursor_down)
+    };
+
+    let presses = if distance > xiv_handle.max_scroll {
+        log::warn!(
+            "scrolling {} entries exceeds the limit of {}",
+            distance,
+            xiv_handle.max_scroll
+        );
+        xiv_handle.max_scroll
+    } else {
+        distance
+    };
+    for _ in 0..presses {
+        cursor(xiv_handle);
+    }
+}
+
+// Select the entry at |index| in a list, starting from the top.
+pub fn select_index(xiv_handle: super::XivHandle, index: usize) {
+    nav_span!(DEBUG, "select_index", index);
+    move_to_index(xiv_handle, 0, index);
+    press_confirm(xiv_handle);
+}
+
+// Scroll down the focused list until |read_label| reports that the
+// highlighted entry is |target|, then select it. Gives up after scrolling
+// |max| times, returning whether the entry was found. |read_label| is called
+// once per entry, after the cursor has come to rest on it.
+pub fn find_in_list_by_name<F>(
+    xiv_handle: super::XivHandle,
+    mut read_label: F,
+    target: &str,
+    max: usize,
+) -> Result<bool, Error>
+where
+    F: FnMut() -> Result<String, Error>,
+{
+    nav_span!(DEBUG, "find_in_list_by_name", target, max);
+    for i in 0..=max {
+        if i > 0 {
+            cursor_down(xiv_handle);
+        }
+        if is_cancelled(xiv_handle) {
+            return Err(anyhow!("cancelled while looking for {}", target));
+        }
+        let label = read_label()?;
+        log::trace!("entry {}: {}", i, label);
+        if label == target {
+            press_confirm(xiv_handle);
+            return Ok(true);
+        }
+    }
+    log::warn!("{} not found in the first {} entries", target, max + 1);
+    Ok(false)
+}
+
+// Press confirm until |predicate| reports that the UI has reached the state
+// we expect, such as a window having opened. Gives up with an error once
+// |timeout| has passed.
+pub fn confirm_until<P>(
+    xiv_handle: super::XivHandle,
+    mut predicate: P,
+    timeout: Duration,
+) -> Result<(), Error>
+where
+    P: FnMut() -> bool,
+{
+    nav_span!(DEBUG, "confirm_until");
+    navigate_with_timeout(xiv_handle, timeout, || {
+        press_confirm(xiv_handle);
+        wait(xiv_handle, VERIFY_DELAY);
+        predicate()
+    })
+}
+
+// The error returned once navigation has given up waiting on the UI. Callers
+// can downcast to this to tell a timeout apart from other failures.
+#[derive(Debug, PartialEq)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UI didn't reach the expected state within {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+// Run |step| until it reports that the UI has reached the state we expect.
+// Each step should send its input and give the UI time to settle before
+// checking. Fails with TimedOut once |timeout| has passed, so automation that
+// polls the UI can't hang forever.
+pub fn navigate_with_timeout<F>(
+    xiv_handle: super::XivHandle,
+    timeout: Duration,
+    mut step: F,
+) -> Result<(), Error>
+where
+    F: FnMut() -> bool,
+{
+    let deadline = now() + timeout;
+    loop {
+        if step() {
+            return Ok(());
+        }
+        if is_cancelled(xiv_handle) {
+            return Err(anyhow!("cancelled while waiting on the UI"));
+        }
+        if now() >= deadline {
+            return Err(TimedOut(timeout).into());
+        }
+    }
+}
+
+// Game windows that can be opened directly, either by text command or by
+// keybind.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameWindow {
+    ArmouryChest,
+    Character,
+    CraftingLog,
+    Inventory,
+    RetainerList,
+    Timers,
+}
+
+// How a GameWindow gets opened.
+enum Opener {
+    Command(&'static str),
+    Keybind(fn(&KeyBindings) -> i32),
+    SummoningBell,
+}
+
+impl GameWindow {
+    fn opener(self) -> Opener {
+        match self {
+            GameWindow::ArmouryChest => Opener::Command("/armourychest"),
+            GameWindow::Character => Opener::Command("/character"),
+            GameWindow::Inventory => Opener::Command("/inventory"),
+            GameWindow::Timers => Opener::Command("/timers"),
+            // The crafting log is opened with its keybind, N by default.
+            GameWindow::CraftingLog => Opener::Keybind(|keys| keys.crafting_log),
+            // There's no command for the retainer list, it can only be
+            // reached by interacting with a summoning bell.
+            GameWindow::RetainerList => Opener::SummoningBell,
+        }
+    }
+
+    // The text command that opens the window, if it has one.
+    pub fn command(self) -> Option<&'static str> {
+        match self.opener() {
+            Opener::Command(command) => Some(command),
+            Opener::Keybind(_) | Opener::SummoningBell => None,
+        }
+    }
+}
+
+// Open |window|. Windows without a text command are opened by keybind, other
+// than the retainer list, which expects the player to be standing next to a
+// summoning bell.
+pub fn open_window(xiv_handle: super::XivHandle, window: GameWindow) {
+    nav_span!(DEBUG, "open_window", window = ?window);
+    log::debug!("[open {:?}]", window);
+    match window.opener() {
+        Opener::Command(command) => {
+            press_enter(xiv_handle);
+            send_string(xiv_handle, command);
+            press_enter(xiv_handle);
+        }
+        Opener::Keybind(key) => send_key(xiv_handle, key(&xiv_handle.keys)),
+        Opener::SummoningBell => {
+            target_nearest_npc(xiv_handle);
+            wait(xiv_handle, 1.0);
+            press_confirm(xiv_handle);
+        }
+    }
+}
+
+// What target picks, each by way of its chat command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TargetKind {
+    NearestNpc,
+    NearestEnemy,
+    ByName(String),
+}
+
+impl TargetKind {
+    pub fn command(&self) -> String {
+        match self {
+            TargetKind::NearestNpc => "/tnpc".to_string(),
+            TargetKind::NearestEnemy => "/targetenemy".to_string(),
+            TargetKind::ByName(name) => format!("/target {}", name),
+        }
+    }
+}
+
+pub fn target(xiv_handle: super::XivHandle, kind: TargetKind) {
+    nav_span!(DEBUG, "target", kind = ?kind);
     press_enter(xiv_handle);
-    send_string(xiv_handle, "/tnpc");
+    send_string(xiv_handle, &kind.command());
     press_enter(xiv_handle);
 }
 
+pub fn target_nearest_npc(xiv_handle: super::XivHandle) {
+    target(xiv_handle, TargetKind::NearestNpc);
+}
+
 pub fn send_string(xiv_handle: super::XivHandle, s: &str) {
+    nav_span!(DEBUG, "send_string", len = s.len());
     log::trace!("sending string: '{}'\n", s);
     for c in s.chars() {
         send_char(xiv_handle, c);
     }
 }
 
-pub fn send_action(xiv_handle: super::XivHandle, s: &str, _delay: Option<i64>) {
+// Type |s| with its characters sent back to back, skipping the delay and
+// pacing send_string uses. Only safe where the game buffers input generously,
+// like the chat input; elsewhere, or under heavy lag, characters can be
+// dropped. Waits once at the end so the game can catch up before any more
+// input arrives.
+pub fn send_string_fast(xiv_handle: super::XivHandle, s: &str) {
+    nav_span!(DEBUG, "send_string_fast", len = s.len());
+    log::trace!("sending string fast: '{}'\n", s);
+    let mut buf = [0; 2];
+    for c in s.chars() {
+        if is_cancelled(xiv_handle) {
+            return;
+        }
+        for unit in c.encode_utf16(&mut buf) {
+            post_msg_always(xiv_handle, constants::MSG_KEY_CHAR, *unit as i32, 0);
+        }
+    }
+    wait(xiv_handle, CHAR_DELAY);
+}
+
+// The clipboard operations paste_string needs, so that pasting can be
+// exercised without touching the real clipboard.
+#[cfg_attr(not(windows), allow(dead_code))]
+trait Clipboard {
+    fn contents(&mut self) -> Option<String>;
+    fn set_contents(&mut self, s: &str) -> Result<(), Error>;
+}
+
+#[cfg(windows)]
+impl Clipboard for ClipboardContext {
+    fn contents(&mut self) -> Option<String> {
+        self.get_contents().ok()
+    }
+
+    fn set_contents(&mut self, s: &str) -> Result<(), Error> {
+        ClipboardProvider::set_contents(self, s.to_string()).map_err(|e| anyhow!("{}", e))
+    }
+}
+
+// Paste |s| into the focused text input by way of the clipboard, which is far
+// faster than typing long strings one character at a time. Only text on the
+// clipboard is preserved, and it is put back once the game has had a chance
+// to paste. If the clipboard can't be used this falls back on send_string.
+pub fn paste_string(xiv_handle: super::XivHandle, s: &str) {
+    nav_span!(DEBUG, "paste_string", len = s.len());
+    log::trace!("pasting string: '{}'\n", s);
+    #[cfg(windows)]
+    match ClipboardContext::new() {
+        Ok(mut ctx) => return paste_with(xiv_handle, &mut ctx, s),
+        Err(e) => log::debug!("clipboard unavailable ({}), typing instead", e),
+    }
     send_string(xiv_handle, s);
-    wait(0.5);
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn paste_with<C: Clipboard>(xiv_handle: super::XivHandle, clipboard: &mut C, s: &str) {
+    let previous = clipboard.contents();
+    if let Err(e) = clipboard.set_contents(s) {
+        log::debug!("unable to set clipboard ({}), typing instead", e);
+        return send_string(xiv_handle, s);
+    }
+
+    send_key_with_modifier(xiv_handle, KEY_CONTROL, 'V' as i32);
+    // If the game reads the clipboard after it has been restored it pastes
+    // the previous contents instead, which could be anything. Give it as long
+    // as any other UI change before putting them back.
+    wait(xiv_handle, VERIFY_DELAY);
+
+    if let Some(previous) = previous {
+        if let Err(e) = clipboard.set_contents(&previous) {
+            log::warn!("unable to restore the clipboard: {}", e);
+        }
+    }
+}
+
+// Type |s| then submit it with enter, waiting |delay_ms| milliseconds in
+// between. Half a second is used if no delay is given.
+pub fn send_action(xiv_handle: super::XivHandle, s: &str, delay_ms: Option<i64>) {
+    nav_span!(DEBUG, "send_action", delay_ms = ?delay_ms);
+    send_string(xiv_handle, s);
+    let secs = delay_ms.map_or(0.5, |ms| ms.max(0) as f32 / 1000_f32);
+    wait(xiv_handle, secs);
     press_enter(xiv_handle);
 }
 
@@ -147,6 +902,7 @@ pub fn send_action(xiv_handle: super::XivHandle, s: &str, _delay: Option<i64>) {
 // attempts to deal with that. This has been tested in environments
 // as low as 11 fps.
 pub fn clear_window(xiv_handle: super::XivHandle) {
+    nav_span!(DEBUG, "clear_window");
     log::debug!("clearing the game window");
     // If the text input has focus, try clearing the text to prevent
     // saying junk in a linkshell, /say, etc.
@@ -178,28 +934,1190 @@ pub fn clear_window(xiv_handle: super::XivHandle) {
     press_cancel(xiv_handle);
 }
 
+// Like clear_window, but only presses cancel for as long as |is_clean| reports
+// that a window is still open or the text input has focus. Nothing is sent
+// when the UI is already clean. Fails with TimedOut once |timeout| has passed.
+pub fn clear_window_verified<P>(
+    xiv_handle: super::XivHandle,
+    mut is_clean: P,
+    timeout: Duration,
+) -> Result<(), Error>
+where
+    P: FnMut() -> bool,
+{
+    nav_span!(DEBUG, "clear_window_verified");
+    if is_clean() {
+        return Ok(());
+    }
+    navigate_with_timeout(xiv_handle, timeout, || {
+        press_cancel(xiv_handle);
+        wait(xiv_handle, VERIFY_DELAY);
+        is_clean()
+    })
+}
+
 pub fn send_char(xiv_handle: super::XivHandle, c: char) {
     log::trace!("char: {}", c);
-    send_msg(xiv_handle, constants::MSG_KEY_CHAR, c as i32);
+    // WM_CHAR carries UTF-16 code units, so characters outside of the BMP
+    // are sent as a surrogate pair.
+    let mut buf = [0; 2];
+    for unit in c.encode_utf16(&mut buf) {
+        send_msg(xiv_handle, constants::MSG_KEY_CHAR, *unit as i32);
+    }
     // TODO: Redo this when we have a better timing system
-    wait(CHAR_DELAY);
+    // The pacer takes this (possibly jittered) gap as the one to keep, rather
+    // than padding a short one back up to CHAR_DELAY.
+    let gap = delay(xiv_handle, CHAR_DELAY);
+    set_next_char(xiv_handle, now() + gap);
+    sleep_handle(xiv_handle, gap);
+}
+
+// Press the key called |name|, e.g. "Enter", "F1", "Numpad0" or "A", so
+// macros kept in config files don't need to know virtual key codes.
+pub fn send_named_key(xiv_handle: super::XivHandle, name: &str) -> Result<(), Error> {
+    let key = named_key(name).ok_or_else(|| anyhow!("Unknown key name {:?}", name))?;
+    send_key(xiv_handle, key);
+    Ok(())
+}
+
+// The virtual key code for |name|, ignoring case. Codes are written out
+// rather than taken from winapi so the table reads the same on every
+// platform.
+fn named_key(name: &str) -> Option<i32> {
+    let name = name.to_ascii_lowercase();
+    // The number after |prefix|, written as plain digits without a sign or
+    // leading zeros so that "F01" or "F+1" aren't taken for F1.
+    let number = |prefix: &str| {
+        let digits = name.strip_prefix(prefix)?;
+        let plain = !digits.is_empty()
+            && digits.bytes().all(|b| b.is_ascii_digit())
+            && (digits == "0" || !digits.starts_with('0'));
+        if plain {
+            digits.parse::<i32>().ok()
+        } else {
+            None
+        }
+    };
+
+    // Letters and digits use their ASCII codes.
+    if let [c] = name.as_bytes() {
+        if c.is_ascii_alphanumeric() {
+            return Some(c.to_ascii_uppercase() as i32);
+        }
+    }
+    if let Some(n @ 1..=24) = number("f") {
+        return Some(0x6F + n);
+    }
+    if let Some(n @ 0..=9) = number("numpad") {
+        return Some(0x60 + n);
+    }
+
+    let key = match name.as_str() {
+        "backspace" => 0x08,
+        "tab" => 0x09,
+        "enter" | "return" => 0x0D,
+        "shift" => 0x10,
+        "control" | "ctrl" => 0x11,
+        "alt" => 0x12,
+        "escape" | "esc" => 0x1B,
+        "space" => 0x20,
+        "pageup" => 0x21,
+        "pagedown" => 0x22,
+        "end" => 0x23,
+        "home" => 0x24,
+        "left" => 0x25,
+        "up" => 0x26,
+        "right" => 0x27,
+        "down" => 0x28,
+        "insert" => 0x2D,
+        "delete" => 0x2E,
+        "multiply" => 0x6A,
+        "add" => 0x6B,
+        "subtract" => 0x6D,
+        "decimal" => 0x6E,
+        "divide" => 0x6F,
+        _ => return None,
+    };
+    Some(key)
 }
 
 pub fn send_key(xiv_handle: super::XivHandle, c: i32) {
+    nav_span!(TRACE, "send_key", key = c);
     log::trace!("key {:x}", c);
-    send_msg(xiv_handle, constants::MSG_KEY_DOWN, c);
-    send_msg(xiv_handle, constants::MSG_KEY_UP, c);
-    if xiv_handle.use_slow_navigation {
-        wait(UI_DELAY_SLOW);
-    } else {
-        wait(UI_DELAY);
+    post_press(
+        xiv_handle,
+        &[
+            (constants::MSG_KEY_DOWN, c, 0),
+            (constants::MSG_KEY_UP, c, 0),
+        ],
+    );
+    wait(xiv_handle, UI_DELAY);
+}
+
+// Press |key| while |modifier| is held, e.g. KEY_CONTROL for Ctrl+V or
+// KEY_ALT for the alternate hotbar bindings.
+pub fn send_key_with_modifier(xiv_handle: super::XivHandle, modifier: i32, key: i32) {
+    nav_span!(TRACE, "send_key_with_modifier", modifier, key);
+    log::trace!("key {:x} with modifier {:x}", key, modifier);
+    post_press(
+        xiv_handle,
+        &[
+            (constants::MSG_KEY_DOWN, modifier, 0),
+            (constants::MSG_KEY_DOWN, key, 0),
+            (constants::MSG_KEY_UP, key, 0),
+            (constants::MSG_KEY_UP, modifier, 0),
+        ],
+    );
+    wait(xiv_handle, UI_DELAY);
+}
+
+// Hold |key| down for |duration|, for example to keep the character moving.
+// The wait isn't affected by the speed factor, but is cut short if the handle
+// is cancelled. The key is always released afterwards.
+pub fn hold_key(xiv_handle: super::XivHandle, key: i32, duration: Duration) {
+    nav_span!(DEBUG, "hold_key", key);
+    log::trace!("holding key {:x} for {:?}", key, duration);
+
+    // Releases the key once dropped so that an early return or panic while
+    // waiting can't leave the character running.
+    struct Release(super::XivHandle, i32);
+    impl Drop for Release {
+        fn drop(&mut self) {
+            post_msg_always(self.0, constants::MSG_KEY_UP, self.1, 0);
+        }
     }
+
+    send_msg(xiv_handle, constants::MSG_KEY_DOWN, key);
+    let _release = Release(xiv_handle, key);
+    sleep_handle(xiv_handle, duration);
+}
+
+// Movement using the handle's key bindings.
+pub fn move_forward(xiv_handle: super::XivHandle, duration: Duration) {
+    hold_key(xiv_handle, xiv_handle.keys.move_forward, duration);
+}
+
+pub fn move_backward(xiv_handle: super::XivHandle, duration: Duration) {
+    hold_key(xiv_handle, xiv_handle.keys.move_backward, duration);
+}
+
+pub fn strafe_left(xiv_handle: super::XivHandle, duration: Duration) {
+    hold_key(xiv_handle, xiv_handle.keys.strafe_left, duration);
+}
+
+pub fn strafe_right(xiv_handle: super::XivHandle, duration: Duration) {
+    hold_key(xiv_handle, xiv_handle.keys.strafe_right, duration);
+}
+
+// Left click at |x|, |y| in the game window. Coordinates are relative to the
+// top left of the window's client area.
+pub fn click(xiv_handle: super::XivHandle, x: i32, y: i32) {
+    log::debug!("[click {}, {}]", x, y);
+    send_click(
+        xiv_handle,
+        constants::MSG_LBUTTON_DOWN,
+        constants::MSG_LBUTTON_UP,
+        constants::MOUSE_LBUTTON,
+        x,
+        y,
+    );
+}
+
+// Right click at |x|, |y| in the game window's client area.
+pub fn right_click(xiv_handle: super::XivHandle, x: i32, y: i32) {
+    log::debug!("[right click {}, {}]", x, y);
+    send_click(
+        xiv_handle,
+        constants::MSG_RBUTTON_DOWN,
+        constants::MSG_RBUTTON_UP,
+        constants::MOUSE_RBUTTON,
+        x,
+        y,
+    );
+}
+
+fn send_click(xiv_handle: super::XivHandle, down: u32, up: u32, button: i32, x: i32, y: i32) {
+    nav_span!(TRACE, "click", x, y);
+    let pos = pack_coordinates(x, y);
+    post_press(xiv_handle, &[(down, button, pos), (up, 0, pos)]);
+    wait(xiv_handle, UI_DELAY);
+}
+
+// Mouse messages carry the client coordinates in LPARAM, with x in the low
+// word and y in the high word.
+fn pack_coordinates(x: i32, y: i32) -> isize {
+    (((y & 0xFFFF) << 16) | (x & 0xFFFF)) as isize
 }
 
 // Send a character/key to the XIV window
-fn send_msg(_xiv_handle: super::XivHandle, _msg: u32, _key: i32) {
-    #[cfg(windows)]
+fn send_msg(xiv_handle: super::XivHandle, msg: u32, key: i32) {
+    post_msg(xiv_handle, msg, key, 0);
+}
+
+fn post_msg(xiv_handle: super::XivHandle, msg: u32, key: i32, lparam: isize) {
+    // Once cancelled, drop any remaining input so navigation sequences wind
+    // down as quickly as possible.
+    if is_cancelled(xiv_handle) {
+        log::trace!("cancelled, dropping msg {:x}", msg);
+        return;
+    }
+    // The second half of a surrogate pair belongs to the character before it.
+    if msg == constants::MSG_KEY_CHAR && !(0xDC00..0xE000).contains(&key) {
+        pace_char(xiv_handle);
+    }
+    post_msg_always(xiv_handle, msg, key, lparam);
+}
+
+// Post |msgs| as one press, e.g. a key down and up. Cancellation is only
+// checked before the first message so a cancel can't leave anything held down.
+fn post_press(xiv_handle: super::XivHandle, msgs: &[(u32, i32, isize)]) {
+    if is_cancelled(xiv_handle) {
+        log::trace!("cancelled, dropping press");
+        return;
+    }
+    for (msg, key, lparam) in msgs {
+        post_msg_always(xiv_handle, *msg, *key, *lparam);
+    }
+}
+
+// Post a message straight away, without checking for cancellation or pacing
+// characters. Callers handle those themselves, e.g. a held key is released
+// even once cancelled.
+fn post_msg_always(xiv_handle: super::XivHandle, msg: u32, key: i32, lparam: isize) {
+    match xiv_handle.input_method {
+        InputMethod::SendInput => match synthetic_input(msg, key, lparam) {
+            Some(input) => send_input(xiv_handle, input),
+            None => {
+                log::debug!("msg {:x} has no SendInput equivalent, posting it", msg);
+                post_message(xiv_handle, msg, key, lparam);
+            }
+        },
+        InputMethod::PostMessage => post_message(xiv_handle, msg, key, lparam),
+    }
+}
+
+fn post_message(xiv_handle: super::XivHandle, msg: u32, key: i32, lparam: isize) {
+    if record_event(Event::Message(msg, key, lparam)) {
+        return;
+    }
+    if xiv_handle.dry_run {
+        log::info!("dry run: msg {:x} key {:x} lparam {:x}", msg, key, lparam);
+    } else {
+        #[cfg(windows)]
+        unsafe {
+            PostMessageA(xiv_handle.hwnd, msg as UINT, key as usize, lparam);
+        }
+    }
+}
+
+fn send_input(xiv_handle: super::XivHandle, input: SyntheticInput) {
+    if record_event(Event::Input(input)) {
+        return;
+    }
+    if xiv_handle.dry_run {
+        log::info!("dry run: input {:?}", input);
+    } else {
+        #[cfg(windows)]
+        inject_input(xiv_handle, input);
+    }
+}
+
+// SendInput goes to whichever window has focus, so nothing is sent unless
+// that is the game.
+#[cfg(windows)]
+fn inject_input(xiv_handle: super::XivHandle, input: SyntheticInput) {
     unsafe {
-        PostMessageA(_xiv_handle.hwnd, _msg as UINT, _key as usize, 0);
+        if GetForegroundWindow() != xiv_handle.hwnd {
+            SetForegroundWindow(xiv_handle.hwnd);
+        }
+        // Windows' foreground lock often refuses to hand focus over to a
+        // background process, so check it actually moved.
+        if GetForegroundWindow() != xiv_handle.hwnd {
+            log::warn!("the game window doesn't have focus, dropping {:?}", input);
+            return;
+        }
+
+        let keyboard = |key: u16, scan: u16, flags: DWORD| {
+            let mut input: INPUT = std::mem::zeroed();
+            input.type_ = INPUT_KEYBOARD;
+            *input.u.ki_mut() = KEYBDINPUT {
+                wVk: key,
+                wScan: scan,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            };
+            input
+        };
+        let mut inputs = match input {
+            SyntheticInput::Key { key, up } => {
+                vec![keyboard(key, 0, if up { KEYEVENTF_KEYUP } else { 0 })]
+            }
+            // A character is typed as a down and up of the UTF-16 unit.
+            SyntheticInput::Char(unit) => vec![
+                keyboard(0, unit, KEYEVENTF_UNICODE),
+                keyboard(0, unit, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP),
+            ],
+            // Mouse input goes wherever the cursor is, so move it over the
+            // point in the game window first.
+            SyntheticInput::Mouse { right, up, x, y } => {
+                let mut point = POINT { x, y };
+                ClientToScreen(xiv_handle.hwnd, &mut point);
+                SetCursorPos(point.x, point.y);
+                let flags = match (right, up) {
+                    (false, false) => MOUSEEVENTF_LEFTDOWN,
+                    (false, true) => MOUSEEVENTF_LEFTUP,
+                    (true, false) => MOUSEEVENTF_RIGHTDOWN,
+                    (true, true) => MOUSEEVENTF_RIGHTUP,
+                };
+                let mut input: INPUT = std::mem::zeroed();
+                input.type_ = INPUT_MOUSE;
+                input.u.mi_mut().dwFlags = flags;
+                vec![input]
+            }
+        };
+        SendInput(
+            inputs.len() as UINT,
+            inputs.as_mut_ptr(),
+            std::mem::size_of::<INPUT>() as c_int,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_handle() -> crate::XivHandle {
+        crate::XivHandle::new(std::ptr::null_mut())
+    }
+
+    fn wait_event(s: f32) -> Event {
+        Event::Wait(Duration::from_millis((s * 1000_f32) as u64))
+    }
+
+    fn char_events(s: &str) -> Vec<Event> {
+        let mut events = Vec::new();
+        for c in s.chars() {
+            events.push(Event::Message(constants::MSG_KEY_CHAR, c as i32, 0));
+            events.push(wait_event(CHAR_DELAY));
+        }
+        events
+    }
+
+    fn key_events(key: i32, count: usize) -> Vec<Event> {
+        let mut events = Vec::new();
+        for _ in 0..count {
+            events.push(Event::Message(constants::MSG_KEY_DOWN, key, 0));
+            events.push(Event::Message(constants::MSG_KEY_UP, key, 0));
+            events.push(wait_event(UI_DELAY));
+        }
+        events
+    }
+
+    #[test]
+    fn record_clear_window() {
+        let events = record(|| clear_window(test_handle()));
+
+        let mut expected = Vec::new();
+        expected.extend(key_events(constants::KEY_BACKSPACE, 32));
+        expected.extend(key_events(constants::KEY_ENTER, 1));
+        expected.extend(key_events(constants::KEY_BACKSPACE, 32));
+        expected.extend(key_events(constants::KEY_ENTER, 1));
+        expected.extend(key_events(constants::KEY_ESCAPE, 4));
+        expected.extend(key_events(constants::KEY_CANCEL, 13));
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn record_is_per_call() {
+        let first = record(|| press_confirm(test_handle()));
+        let second = record(|| {});
+        assert_eq!(first, key_events(constants::KEY_CONFIRM, 1));
+        assert!(second.is_empty());
+    }
+
+    #[test]
+    fn remapped_confirm() {
+        let mut handle = test_handle();
+        handle.keys.confirm = 'F' as i32;
+        let events = record(|| press_confirm(handle));
+        assert_eq!(events, key_events('F' as i32, 1));
+    }
+
+    #[test]
+    fn record_clicks() {
+        let events = record(|| {
+            click(test_handle(), 100, 200);
+            right_click(test_handle(), 3, 4);
+        });
+        let wait = wait_event(UI_DELAY);
+        assert_eq!(
+            events,
+            vec![
+                Event::Message(
+                    constants::MSG_LBUTTON_DOWN,
+                    constants::MOUSE_LBUTTON,
+                    0x00C8_0064
+                ),
+                Event::Message(constants::MSG_LBUTTON_UP, 0, 0x00C8_0064),
+                wait,
+                Event::Message(
+                    constants::MSG_RBUTTON_DOWN,
+                    constants::MOUSE_RBUTTON,
+                    0x0004_0003
+                ),
+                Event::Message(constants::MSG_RBUTTON_UP, 0, 0x0004_0003),
+                wait,
+            ]
+        );
+    }
+
+    #[test]
+    fn send_char_surrogate_pair() {
+        let wait = wait_event(CHAR_DELAY);
+        assert_eq!(
+            record(|| send_char(test_handle(), 'a')),
+            vec![Event::Message(constants::MSG_KEY_CHAR, 0x61, 0), wait]
+        );
+        assert_eq!(
+            record(|| send_char(test_handle(), '\u{1F600}')),
+            vec![
+                Event::Message(constants::MSG_KEY_CHAR, 0xD83D, 0),
+                Event::Message(constants::MSG_KEY_CHAR, 0xDE00, 0),
+                wait,
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_wait_returns_early() {
+        static TOKEN: CancelToken = CancelToken::new();
+        let canceller = std::thread::spawn(|| {
+            sleep(Duration::from_millis(50));
+            TOKEN.cancel();
+        });
+
+        let start = Instant::now();
+        assert!(!wait_cancellable(10.0, &TOKEN));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        canceller.join().unwrap();
+
+        TOKEN.reset();
+        assert!(wait_cancellable(0.01, &TOKEN));
+    }
+
+    #[test]
+    fn cancelled_handle_sends_nothing() {
+        static TOKEN: CancelToken = CancelToken::new();
+        let mut handle = test_handle();
+        handle.cancel = Some(&TOKEN);
+        TOKEN.cancel();
+        let events = record(|| press_confirm(handle));
+        assert_eq!(events, vec![wait_event(UI_DELAY)]);
+    }
+
+    #[test]
+    fn speed_factor_scales_delays() {
+        let mut handle = test_handle();
+        assert_eq!(delay(handle, 0.5), Duration::from_millis(500));
+        handle.speed_factor = 2.0;
+        assert_eq!(delay(handle, 0.5), Duration::from_millis(1000));
+        assert_eq!(
+            record(|| wait(handle, 0.5)),
+            vec![Event::Wait(Duration::from_millis(1000))]
+        );
+        assert_eq!(
+            record(|| send_char(handle, 'a'))[1],
+            wait_event(CHAR_DELAY * 2.0)
+        );
+    }
+
+    #[test]
+    fn jitter_stays_in_band() {
+        let mut handle = test_handle();
+        handle.jitter = 0.2;
+        seed_jitter(1234);
+        let first = record(|| {
+            for _ in 0..100 {
+                wait(handle, 1.0);
+            }
+        });
+        for event in &first {
+            match event {
+                Event::Wait(d) => assert!(
+                    *d >= Duration::from_millis(800) && *d <= Duration::from_millis(1200),
+                    "{:?} outside of the jitter band",
+                    d
+                ),
+                _ => panic!("unexpected event {:?}", event),
+            }
+        }
+        assert!(first.iter().any(|e| *e != first[0]));
+
+        // The same seed should produce the same delays.
+        seed_jitter(1234);
+        let second = record(|| {
+            for _ in 0..100 {
+                wait(handle, 1.0);
+            }
+        });
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn slow_navigation_scales_delays() {
+        let mut handle = test_handle();
+        handle.use_slow_navigation = true;
+        assert_eq!(
+            record(|| wait(handle, 1.0)),
+            vec![Event::Wait(Duration::from_millis(2000))]
+        );
+        assert_eq!(
+            record(|| send_char(handle, 'a'))[1],
+            wait_event(CHAR_DELAY * SLOW_NAVIGATION_FACTOR)
+        );
+        assert_eq!(
+            record(|| press_confirm(handle))[2],
+            Event::Wait(Duration::from_millis(200))
+        );
+    }
+
+    #[test]
+    fn send_action_delay() {
+        let mut expected = vec![
+            Event::Message(constants::MSG_KEY_CHAR, 'a' as i32, 0),
+            wait_event(CHAR_DELAY),
+            Event::Wait(Duration::from_millis(1500)),
+        ];
+        expected.extend(key_events(constants::KEY_ENTER, 1));
+        assert_eq!(
+            record(|| send_action(test_handle(), "a", Some(1500))),
+            expected
+        );
+
+        expected[2] = Event::Wait(Duration::from_millis(500));
+        assert_eq!(record(|| send_action(test_handle(), "a", None)), expected);
+    }
+
+    #[test]
+    fn hotbar_slots() {
+        assert_eq!(
+            record(|| press_hotbar(test_handle(), 1).unwrap()),
+            key_events('1' as i32, 1)
+        );
+        assert_eq!(
+            record(|| press_hotbar(test_handle(), 10).unwrap()),
+            key_events('0' as i32, 1)
+        );
+        assert_eq!(
+            record(|| press_hotbar(test_handle(), 12).unwrap()),
+            key_events(constants::KEY_EQUALS, 1)
+        );
+        assert!(press_hotbar(test_handle(), 0).is_err());
+        assert!(press_hotbar(test_handle(), 13).is_err());
+
+        let mut handle = test_handle();
+        handle.keys.hotbar[1] = 'X' as i32;
+        assert_eq!(
+            record(|| press_hotbar(handle, 2).unwrap()),
+            key_events('X' as i32, 1)
+        );
+    }
+
+    #[test]
+    fn named_keys() {
+        assert_eq!(named_key("Enter"), Some(0x0D));
+        assert_eq!(named_key("esc"), Some(0x1B));
+        assert_eq!(named_key("a"), Some('A' as i32));
+        assert_eq!(named_key("7"), Some('7' as i32));
+        assert_eq!(named_key("F1"), Some(0x70));
+        assert_eq!(named_key("F12"), Some(0x7B));
+        assert_eq!(named_key("Numpad0"), Some(0x60));
+        assert_eq!(named_key("PageDown"), Some(0x22));
+        assert_eq!(named_key("F0"), None);
+        assert_eq!(named_key("F+1"), None);
+        assert_eq!(named_key("F01"), None);
+        assert_eq!(named_key("Numpad+5"), None);
+        assert_eq!(named_key("Numpad05"), None);
+        assert_eq!(named_key("F"), Some('F' as i32));
+        assert_eq!(named_key("Hyper"), None);
+
+        assert_eq!(
+            record(|| send_named_key(test_handle(), "F3").unwrap()),
+            key_events(0x72, 1)
+        );
+        assert!(send_named_key(test_handle(), "").is_err());
+    }
+
+    #[test]
+    fn open_window_commands() {
+        let windows = [
+            (GameWindow::ArmouryChest, "/armourychest"),
+            (GameWindow::Character, "/character"),
+            (GameWindow::Inventory, "/inventory"),
+            (GameWindow::Timers, "/timers"),
+        ];
+        for (window, command) in windows.iter() {
+            let mut expected = key_events(constants::KEY_ENTER, 1);
+            expected.extend(char_events(command));
+            expected.extend(key_events(constants::KEY_ENTER, 1));
+            assert_eq!(record(|| open_window(test_handle(), *window)), expected);
+        }
+
+        assert_eq!(GameWindow::CraftingLog.command(), None);
+        assert_eq!(
+            record(|| open_window(test_handle(), GameWindow::CraftingLog)),
+            key_events('N' as i32, 1)
+        );
+        let mut handle = test_handle();
+        handle.keys.crafting_log = 'L' as i32;
+        assert_eq!(
+            record(|| open_window(handle, GameWindow::CraftingLog)),
+            key_events('L' as i32, 1)
+        );
+
+        assert_eq!(GameWindow::RetainerList.command(), None);
+        let mut expected = record(|| target_nearest_npc(test_handle()));
+        expected.push(wait_event(1.0));
+        expected.extend(key_events(constants::KEY_CONFIRM, 1));
+        assert_eq!(
+            record(|| open_window(test_handle(), GameWindow::RetainerList)),
+            expected
+        );
+    }
+
+    #[test]
+    fn target_commands() {
+        let kinds = [
+            (TargetKind::NearestNpc, "/tnpc"),
+            (TargetKind::NearestEnemy, "/targetenemy"),
+            (
+                TargetKind::ByName("Summoning Bell".to_string()),
+                "/target Summoning Bell",
+            ),
+        ];
+        for (kind, command) in kinds.iter() {
+            let mut expected = key_events(constants::KEY_ENTER, 1);
+            expected.extend(char_events(command));
+            expected.extend(key_events(constants::KEY_ENTER, 1));
+            assert_eq!(record(|| target(test_handle(), kind.clone())), expected);
+        }
+        assert_eq!(
+            record(|| target_nearest_npc(test_handle())),
+            record(|| target(test_handle(), TargetKind::NearestNpc))
+        );
+    }
+
+    #[test]
+    fn list_movement() {
+        assert_eq!(
+            record(|| move_to_index(test_handle(), 5, 2)),
+            key_events(constants::KEY_UP, 3)
+        );
+        assert_eq!(
+            record(|| move_to_index(test_handle(), 2, 4)),
+            key_events(constants::KEY_DOWN, 2)
+        );
+        assert!(record(|| move_to_index(test_handle(), 3, 3)).is_empty());
+
+        let mut expected = key_events(constants::KEY_DOWN, 2);
+        expected.extend(key_events(constants::KEY_CONFIRM, 1));
+        assert_eq!(record(|| select_index(test_handle(), 2)), expected);
+    }
+
+    #[test]
+    fn list_movement_is_clamped() {
+        let mut handle = test_handle();
+        handle.max_scroll = 10;
+        assert_eq!(
+            record(|| move_to_index(handle, 0, 5000)),
+            key_events(constants::KEY_DOWN, 10)
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn navigation_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::{span, Event, Metadata, Subscriber};
+
+        // Collects the name of every span created.
+        struct Capture(Arc<Mutex<Vec<&'static str>>>);
+
+        impl Subscriber for Capture {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                span::Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &span::Id) {}
+            fn exit(&self, _: &span::Id) {}
+        }
+
+        let names = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Capture(names.clone()), || {
+            record(|| select_index(test_handle(), 1));
+        });
+        assert_eq!(
+            *names.lock().unwrap(),
+            vec!["select_index", "move_to_index", "send_key", "send_key"]
+        );
+    }
+
+    #[test]
+    fn find_in_list() {
+        let mut handle = test_handle();
+        handle.keys.down = 0x28;
+        handle.keys.confirm = 0x0D;
+        let labels = ["Alpha", "Beta", "Gamma", "Delta", "Epsilon"];
+
+        let mut read = 0;
+        let events = record(|| {
+            let label = || {
+                read += 1;
+                Ok(labels[read - 1].to_string())
+            };
+            assert!(find_in_list_by_name(handle, label, "Delta", 10).unwrap());
+        });
+        let mut expected = key_events(0x28, 3);
+        expected.extend(key_events(0x0D, 1));
+        assert_eq!(events, expected);
+
+        let mut read = 0;
+        let events = record(|| {
+            let label = || {
+                read += 1;
+                Ok(labels[read - 1].to_string())
+            };
+            assert!(!find_in_list_by_name(handle, label, "Epsilon", 2).unwrap());
+        });
+        assert_eq!(events, key_events(0x28, 2));
+
+        let failing = || Err(anyhow!("unreadable"));
+        assert!(find_in_list_by_name(handle, failing, "Alpha", 2).is_err());
+    }
+
+    #[test]
+    fn confirm_until_predicate() {
+        let mut checks = 0;
+        let events = record(|| {
+            let predicate = || {
+                checks += 1;
+                checks == 2
+            };
+            assert!(confirm_until(test_handle(), predicate, Duration::from_secs(10)).is_ok());
+        });
+
+        let mut expected = key_events(constants::KEY_CONFIRM, 1);
+        expected.push(wait_event(VERIFY_DELAY));
+        expected.extend(expected.clone());
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn confirm_until_timeout() {
+        let events = record(|| {
+            let err = confirm_until(test_handle(), || false, Duration::from_secs(3)).unwrap_err();
+            assert!(err.is::<TimedOut>());
+        });
+        // Each attempt takes 1.1 seconds, so the third is the last.
+        let attempts = events
+            .iter()
+            .filter(|e| **e == wait_event(VERIFY_DELAY))
+            .count();
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn clear_window_verified_closes_modals() {
+        // Two modals are open, each cancel closes one.
+        let mut open = 2;
+        let events = record(|| {
+            let is_clean = || {
+                let clean = open == 0;
+                open -= 1;
+                clean
+            };
+            assert!(
+                clear_window_verified(test_handle(), is_clean, Duration::from_secs(10)).is_ok()
+            );
+        });
+
+        let mut expected = key_events(constants::KEY_CANCEL, 1);
+        expected.push(wait_event(VERIFY_DELAY));
+        expected.extend(expected.clone());
+        assert_eq!(events, expected);
+
+        assert_eq!(
+            record(|| assert!(clear_window_verified(
+                test_handle(),
+                || true,
+                Duration::from_secs(10)
+            )
+            .is_ok())),
+            vec![]
+        );
+        record(|| {
+            let err =
+                clear_window_verified(test_handle(), || false, Duration::from_secs(3)).unwrap_err();
+            assert!(err.is::<TimedOut>());
+        });
+    }
+
+    #[test]
+    fn dry_run_times_out() {
+        let mut handle = test_handle();
+        handle.dry_run = true;
+        let mut checks = 0;
+        let start = Instant::now();
+        let predicate = || {
+            checks += 1;
+            false
+        };
+        let err = confirm_until(handle, predicate, Duration::from_secs(10)).unwrap_err();
+        assert!(err.is::<TimedOut>());
+        // The waits are skipped, but still count towards the timeout.
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(checks, 10);
+    }
+
+    #[test]
+    fn hold_key_sequence() {
+        let mut handle = test_handle();
+        handle.speed_factor = 2.0;
+        assert_eq!(
+            record(|| move_forward(handle, Duration::from_millis(1500))),
+            vec![
+                Event::Message(constants::MSG_KEY_DOWN, 'W' as i32, 0),
+                Event::Wait(Duration::from_millis(1500)),
+                Event::Message(constants::MSG_KEY_UP, 'W' as i32, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn remapped_movement() {
+        let mut handle = test_handle();
+        handle.keys.strafe_left = 'A' as i32;
+        assert_eq!(
+            record(|| strafe_left(handle, Duration::from_secs(1))),
+            vec![
+                Event::Message(constants::MSG_KEY_DOWN, 'A' as i32, 0),
+                Event::Wait(Duration::from_secs(1)),
+                Event::Message(constants::MSG_KEY_UP, 'A' as i32, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn hold_key_released_when_cancelled() {
+        static TOKEN: CancelToken = CancelToken::new();
+        let mut handle = test_handle();
+        handle.cancel = Some(&TOKEN);
+        let events = record(|| {
+            send_msg(handle, constants::MSG_KEY_DOWN, 'W' as i32);
+            TOKEN.cancel();
+            hold_key(handle, 'W' as i32, Duration::from_secs(5));
+        });
+        assert_eq!(
+            events.last(),
+            Some(&Event::Message(constants::MSG_KEY_UP, 'W' as i32, 0))
+        );
+    }
+
+    #[test]
+    fn modifier_key_sequence() {
+        let handle = test_handle();
+        assert_eq!(
+            record(|| send_key_with_modifier(handle, KEY_SHIFT, '3' as i32)),
+            vec![
+                Event::Message(constants::MSG_KEY_DOWN, KEY_SHIFT, 0),
+                Event::Message(constants::MSG_KEY_DOWN, '3' as i32, 0),
+                Event::Message(constants::MSG_KEY_UP, '3' as i32, 0),
+                Event::Message(constants::MSG_KEY_UP, KEY_SHIFT, 0),
+                wait_event(UI_DELAY),
+            ]
+        );
+    }
+
+    thread_local! {
+        static LOGGED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    // Collects info level logs for the current thread.
+    struct InfoLogger;
+
+    impl log::Log for InfoLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                LOGGED.with(|l| l.borrow_mut().push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn dry_run_only_logs() {
+        static LOGGER: InfoLogger = InfoLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Info);
+
+        // Posting to the test handle's null window would go to this thread's
+        // queue without failing. The logs are the guard instead: a message
+        // that was really posted would not have its dry run line logged.
+        let mut handle = test_handle();
+        handle.dry_run = true;
+        let start = Instant::now();
+        send_key(handle, 0x41);
+        assert!(start.elapsed() < Duration::from_secs_f32(UI_DELAY));
+
+        let logged = LOGGED.with(|l| l.borrow().clone());
+        assert_eq!(
+            logged,
+            vec![
+                format!("dry run: msg {:x} key 41 lparam 0", constants::MSG_KEY_DOWN),
+                format!("dry run: msg {:x} key 41 lparam 0", constants::MSG_KEY_UP),
+                format!("dry run: wait {:?}", delay(handle, UI_DELAY)),
+            ]
+        );
+    }
+
+    #[test]
+    fn chars_are_paced() {
+        let handle = test_handle();
+        // Characters posted without waiting are held back by the pacer.
+        assert_eq!(
+            record(|| {
+                send_msg(handle, constants::MSG_KEY_CHAR, 0x61);
+                send_msg(handle, constants::MSG_KEY_CHAR, 0x62);
+            }),
+            vec![
+                Event::Message(constants::MSG_KEY_CHAR, 0x61, 0),
+                wait_event(CHAR_DELAY),
+                Event::Message(constants::MSG_KEY_CHAR, 0x62, 0),
+            ]
+        );
+        // send_char already waits long enough, so nothing is added.
+        assert_eq!(
+            record(|| {
+                send_char(handle, 'a');
+                send_char(handle, 'b');
+            }),
+            char_events("ab")
+        );
+
+        // Jittered gaps below CHAR_DELAY are kept, not padded by the pacer.
+        let mut handle = test_handle();
+        handle.jitter = 0.5;
+        seed_jitter(1234);
+        let events = record(|| send_string(handle, "abcdefghij"));
+        let mut gaps = Vec::new();
+        for pair in events.chunks(2) {
+            match pair {
+                [Event::Message(constants::MSG_KEY_CHAR, _, 0), Event::Wait(d)] => gaps.push(*d),
+                _ => panic!("unexpected events {:?}", pair),
+            }
+        }
+        assert_eq!(gaps.len(), 10);
+        assert!(gaps
+            .iter()
+            .any(|d| *d < Duration::from_secs_f32(CHAR_DELAY)));
+    }
+
+    #[test]
+    fn navigate_times_out() {
+        let handle = test_handle();
+        let mut steps = 0;
+        record(|| {
+            let err = navigate_with_timeout(handle, Duration::from_secs(2), || {
+                steps += 1;
+                wait(handle, 0.5);
+                false
+            })
+            .unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<TimedOut>(),
+                Some(&TimedOut(Duration::from_secs(2)))
+            );
+        });
+        assert_eq!(steps, 4);
+    }
+
+    #[test]
+    fn fast_string_skips_char_delay() {
+        let mut expected: Vec<Event> = "ab\u{1F600}"
+            .encode_utf16()
+            .map(|unit| Event::Message(constants::MSG_KEY_CHAR, unit as i32, 0))
+            .collect();
+        expected.push(wait_event(CHAR_DELAY));
+        assert_eq!(
+            record(|| send_string_fast(test_handle(), "ab\u{1F600}")),
+            expected
+        );
+    }
+
+    #[test]
+    fn input_method_picks_backend() {
+        let mut handle = test_handle();
+        assert_eq!(record(|| send_key(handle, 0x41)), key_events(0x41, 1));
+
+        handle.input_method = InputMethod::SendInput;
+        assert_eq!(
+            record(|| send_key(handle, 0x41)),
+            vec![
+                Event::Input(SyntheticInput::Key {
+                    key: 0x41,
+                    up: false
+                }),
+                Event::Input(SyntheticInput::Key {
+                    key: 0x41,
+                    up: true
+                }),
+                wait_event(UI_DELAY),
+            ]
+        );
+        // Messages SendInput can't reproduce are still posted.
+        assert_eq!(
+            record(|| send_msg(handle, 0x10, 0)),
+            vec![Event::Message(0x10, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn synthetic_input_from_messages() {
+        assert_eq!(InputMethod::default(), InputMethod::PostMessage);
+        assert_eq!(
+            synthetic_input(constants::MSG_KEY_DOWN, 0x41, 0),
+            Some(SyntheticInput::Key {
+                key: 0x41,
+                up: false
+            })
+        );
+        assert_eq!(
+            synthetic_input(constants::MSG_KEY_UP, 0x41, 0),
+            Some(SyntheticInput::Key {
+                key: 0x41,
+                up: true
+            })
+        );
+        assert_eq!(
+            synthetic_input(constants::MSG_KEY_CHAR, 0xD83D, 0),
+            Some(SyntheticInput::Char(0xD83D))
+        );
+        assert_eq!(
+            synthetic_input(constants::MSG_RBUTTON_UP, 0, pack_coordinates(640, 360)),
+            Some(SyntheticInput::Mouse {
+                right: true,
+                up: true,
+                x: 640,
+                y: 360
+            })
+        );
+        assert_eq!(
+            synthetic_input(constants::MSG_LBUTTON_DOWN, 1, pack_coordinates(-5, 20)),
+            Some(SyntheticInput::Mouse {
+                right: false,
+                up: false,
+                x: -5,
+                y: 20
+            })
+        );
+        assert_eq!(synthetic_input(0x10, 0, 0), None);
+    }
+
+    #[test]
+    fn cancel_mid_sequence_releases_keys() {
+        static TOKEN: CancelToken = CancelToken::new();
+        fn cancel_on_shift(event: &Event) {
+            if *event == Event::Message(constants::MSG_KEY_DOWN, KEY_SHIFT, 0) {
+                TOKEN.cancel();
+            }
+        }
+
+        let mut handle = test_handle();
+        handle.cancel = Some(&TOKEN);
+        let events = record_with(
+            || send_key_with_modifier(handle, KEY_SHIFT, '3' as i32),
+            Some(cancel_on_shift),
+        );
+        // The press that was under way is finished, modifier release included.
+        assert_eq!(
+            events,
+            vec![
+                Event::Message(constants::MSG_KEY_DOWN, KEY_SHIFT, 0),
+                Event::Message(constants::MSG_KEY_DOWN, '3' as i32, 0),
+                Event::Message(constants::MSG_KEY_UP, '3' as i32, 0),
+                Event::Message(constants::MSG_KEY_UP, KEY_SHIFT, 0),
+                wait_event(UI_DELAY),
+            ]
+        );
+        // Anything after it is dropped.
+        assert_eq!(
+            record(|| send_key_with_modifier(handle, KEY_SHIFT, '3' as i32)),
+            vec![wait_event(UI_DELAY)]
+        );
+        TOKEN.reset();
+    }
+
+    // Records what is written to it, and can be made to refuse writes.
+    struct TestClipboard {
+        contents: Option<String>,
+        writes: Vec<String>,
+        broken: bool,
+    }
+
+    impl Clipboard for TestClipboard {
+        fn contents(&mut self) -> Option<String> {
+            self.contents.clone()
+        }
+
+        fn set_contents(&mut self, s: &str) -> Result<(), Error> {
+            if self.broken {
+                return Err(anyhow!("clipboard is broken"));
+            }
+            self.writes.push(s.to_string());
+            self.contents = Some(s.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn paste_restores_clipboard() {
+        let mut clipboard = TestClipboard {
+            contents: Some("hunter2".to_string()),
+            writes: Vec::new(),
+            broken: false,
+        };
+        let events = record(|| paste_with(test_handle(), &mut clipboard, "/say hi"));
+        let mut expected =
+            record(|| send_key_with_modifier(test_handle(), KEY_CONTROL, 'V' as i32));
+        expected.push(wait_event(VERIFY_DELAY));
+        assert_eq!(events, expected);
+        assert_eq!(clipboard.writes, vec!["/say hi", "hunter2"]);
+    }
+
+    #[test]
+    fn paste_falls_back_on_typing() {
+        let mut clipboard = TestClipboard {
+            contents: None,
+            writes: Vec::new(),
+            broken: true,
+        };
+        assert_eq!(
+            record(|| paste_with(test_handle(), &mut clipboard, "hi")),
+            char_events("hi")
+        );
     }
 }